@@ -0,0 +1,7 @@
+ALTER TABLE mobile_poc_rewards
+    ADD COLUMN source_key text,
+    ADD COLUMN ingest_batch_id text;
+
+ALTER TABLE iot_poc_rewards
+    ADD COLUMN source_key text,
+    ADD COLUMN ingest_batch_id text;
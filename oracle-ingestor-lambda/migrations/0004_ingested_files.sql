@@ -0,0 +1,5 @@
+CREATE TABLE ingested_files (
+    source_key text PRIMARY KEY,
+    ingest_batch_id text NOT NULL,
+    ingested_at timestamptz NOT NULL DEFAULT now()
+);
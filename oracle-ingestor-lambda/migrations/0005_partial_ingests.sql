@@ -0,0 +1 @@
+ALTER TABLE ingested_files ADD COLUMN partial boolean NOT NULL DEFAULT false;
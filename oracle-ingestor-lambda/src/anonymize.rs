@@ -0,0 +1,39 @@
+use crate::settings;
+use aws_types::SdkConfig;
+use helium_crypto::PublicKey;
+use sha2::{Digest, Sha256};
+
+/// Replaces hotspot keys with a salted hash so datasets can be shared without
+/// exposing gateway identities. The same key always maps to the same value for
+/// a given salt, so rows for one hotspot can still be grouped.
+pub struct Anonymizer {
+    salt: Vec<u8>,
+}
+
+impl Anonymizer {
+    /// Loads the salt from the Secrets Manager secret `secret_id`.
+    pub async fn load(config: &SdkConfig, secret_id: &str) -> anyhow::Result<Self> {
+        let salt = settings::secret_string(config, secret_id).await?;
+        Ok(Self {
+            salt: salt.into_bytes(),
+        })
+    }
+
+    /// Base58 encoded SHA-256 of the salt and key, which fits the 52
+    /// character hotspot_key columns.
+    pub fn anonymize(&self, hotspot_key: &PublicKey) -> String {
+        let digest = Sha256::new()
+            .chain_update(&self.salt)
+            .chain_update(hotspot_key.to_vec())
+            .finalize();
+        bs58::encode(digest).into_string()
+    }
+}
+
+/// The value written to a hotspot_key column.
+pub fn hotspot_key(hotspot_key: &PublicKey, anonymizer: Option<&Anonymizer>) -> String {
+    match anonymizer {
+        Some(anonymizer) => anonymizer.anonymize(hotspot_key),
+        None => hotspot_key.to_string(),
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{
+    event::{self, Target},
+    summary::Summary,
+};
+use anyhow::anyhow;
+use lambda_runtime::Error;
+use serde::{Deserialize, Serialize};
+
+/// An S3 Batch Operations invocation, schema version 1.0.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchJob {
+    pub invocation_schema_version: String,
+    pub invocation_id: String,
+    pub tasks: Vec<Task>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Task {
+    pub task_id: String,
+    /// URL-encoded, like the keys in S3 notifications.
+    pub s3_key: String,
+    pub s3_bucket_arn: String,
+}
+
+/// The per-task results S3 Batch Operations expects back.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchResponse {
+    pub invocation_schema_version: String,
+    pub treat_missing_keys_as: ResultCode,
+    pub invocation_id: String,
+    pub results: Vec<TaskResult>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskResult {
+    pub task_id: String,
+    pub result_code: ResultCode,
+    pub result_string: String,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub enum ResultCode {
+    Succeeded,
+    /// Retried by the job.
+    TemporaryFailure,
+    PermanentFailure,
+}
+
+impl Task {
+    /// Tasks carry no region, so the bucket is assumed to be in the lambda's.
+    pub fn target(&self) -> anyhow::Result<Target> {
+        let bucket = self
+            .s3_bucket_arn
+            .strip_prefix("arn:aws:s3:::")
+            .ok_or_else(|| anyhow!("Unexpected s3BucketArn {}.", self.s3_bucket_arn))?;
+        let region = std::env::var("AWS_REGION")
+            .map_err(|_| anyhow!("AWS_REGION must be set to run batch jobs."))?;
+        Ok(Target {
+            bucket: bucket.to_string(),
+            region,
+            keys: vec![event::decode_key(&self.s3_key)?],
+        })
+    }
+
+    /// Failed files and files cut off by the deadline are failed temporarily
+    /// so the job retries them, with their errors as the result string.
+    pub fn result(&self, summary: Result<Summary, Error>) -> TaskResult {
+        let (result_code, result_string) = match summary {
+            Ok(summary) if !summary.failed.is_empty() => (
+                ResultCode::TemporaryFailure,
+                summary
+                    .failed
+                    .iter()
+                    .map(|failed| format!("{}: {}", failed.key, failed.error))
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            ),
+            Ok(summary) if summary.unprocessed_keys.is_empty() => (
+                ResultCode::Succeeded,
+                serde_json::to_string(&summary).unwrap_or_default(),
+            ),
+            Ok(_) => (
+                ResultCode::TemporaryFailure,
+                "Invocation deadline reached.".to_string(),
+            ),
+            Err(err) => (ResultCode::TemporaryFailure, err.to_string()),
+        };
+        TaskResult {
+            task_id: self.task_id.clone(),
+            result_code,
+            result_string,
+        }
+    }
+
+    /// Tasks whose target can't be resolved, such as an access point ARN,
+    /// fail permanently since retrying them can't help.
+    pub fn invalid(&self, err: anyhow::Error) -> TaskResult {
+        TaskResult {
+            task_id: self.task_id.clone(),
+            result_code: ResultCode::PermanentFailure,
+            result_string: err.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::FailedFile;
+
+    fn task(s3_bucket_arn: &str) -> Task {
+        Task {
+            task_id: "task".to_string(),
+            s3_key: "radio_reward_share.1671148800000.gz".to_string(),
+            s3_bucket_arn: s3_bucket_arn.to_string(),
+        }
+    }
+
+    #[test]
+    fn maps_summaries_to_result_codes() {
+        let task = task("arn:aws:s3:::oracles");
+        let failed = Summary {
+            failed: vec![FailedFile {
+                key: "radio_reward_share.1.gz".to_string(),
+                error: "corrupt".to_string(),
+            }],
+            ..Default::default()
+        };
+        let unprocessed = Summary {
+            unprocessed_keys: vec!["radio_reward_share.1.gz".to_string()],
+            ..Default::default()
+        };
+
+        let code = |summary| task.result(summary).result_code;
+        assert_eq!(code(Ok(Summary::default())), ResultCode::Succeeded);
+        assert_eq!(code(Ok(failed)), ResultCode::TemporaryFailure);
+        assert_eq!(code(Ok(unprocessed)), ResultCode::TemporaryFailure);
+        assert_eq!(code(Err("timeout".into())), ResultCode::TemporaryFailure);
+        assert_eq!(
+            task.result(Ok(Summary::default())).result_string,
+            serde_json::to_string(&Summary::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_access_point_arns_permanently() {
+        let task = task("arn:aws:s3:us-west-2:123456789012:accesspoint/oracles");
+        let err = task.target().unwrap_err();
+        assert_eq!(task.invalid(err).result_code, ResultCode::PermanentFailure);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{batch::BatchJob, sweep::Sweep};
+use anyhow::anyhow;
+use aws_lambda_events::event::s3::S3Event;
+use serde::Deserialize;
+
+/// Payloads the lambda is invoked with.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Event {
+    S3(S3Event),
+    /// An S3 Batch Operations task list.
+    Batch(BatchJob),
+    /// `{"sweep": {...}}`, the constant input of a scheduled rule.
+    Sweep {
+        sweep: Sweep,
+    },
+}
+
+/// The objects an S3 notification reports as created.
+#[derive(Debug)]
+pub struct Target {
+    pub bucket: String,
+    pub region: String,
+    pub keys: Vec<String>,
+}
+
+/// Collects the created objects from every record in the event. Records for
+/// other events, such as deletions, are ignored.
+pub fn target(event: S3Event) -> anyhow::Result<Option<Target>> {
+    // guard against empty records
+    if event.records.is_empty() {
+        return Err(anyhow!("Event records are unexpectedly empty."));
+    }
+
+    let mut target: Option<Target> = None;
+    for record in event.records {
+        // covers Put, Post, Copy and CompleteMultipartUpload
+        let event_name = record.event_name.unwrap_or_default();
+        if !event_name.starts_with("ObjectCreated:") {
+            tracing::info!(event_name, "ignoring event");
+            continue;
+        }
+
+        let bucket = record
+            .s3
+            .bucket
+            .name
+            .ok_or_else(|| anyhow!("Event record has no bucket name."))?;
+        let region = record
+            .aws_region
+            .ok_or_else(|| anyhow!("Event record has no awsRegion."))?;
+        let key = record
+            .s3
+            .object
+            .key
+            .ok_or_else(|| anyhow!("Event record has no object key."))?;
+        let key = decode_key(&key)?;
+
+        match &mut target {
+            Some(target) if target.bucket == bucket => target.keys.push(key),
+            Some(target) => {
+                return Err(anyhow!(
+                    "Event records span buckets {} and {bucket}.",
+                    target.bucket
+                ))
+            }
+            None => {
+                target = Some(Target {
+                    bucket,
+                    region,
+                    keys: vec![key],
+                })
+            }
+        }
+    }
+    Ok(target)
+}
+
+/// Keys in S3 notifications are form encoded, with spaces as `+`.
+pub fn decode_key(key: &str) -> anyhow::Result<String> {
+    Ok(urlencoding::decode(&key.replace('+', " "))?.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_plus_as_space() {
+        assert_eq!(
+            decode_key("radio_reward_share.1671148800000+(1).gz").unwrap(),
+            "radio_reward_share.1671148800000 (1).gz"
+        );
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(
+            decode_key("backfill%2Fradio_reward_share.1671148800000%2B1.gz").unwrap(),
+            "backfill/radio_reward_share.1671148800000+1.gz"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    settings::Settings,
+    source::{self, Bucket},
+};
+use anyhow::anyhow;
+use helium_crypto::PublicKey;
+use std::{collections::HashSet, str::FromStr};
+
+/// Drops rows for hotspots missing from the allowlist or present in the
+/// denylist, so compliance filtering happens at ingestion.
+#[derive(Debug, Default)]
+pub struct HotspotFilter {
+    allow: Option<HashSet<String>>,
+    deny: HashSet<String>,
+}
+
+impl HotspotFilter {
+    pub async fn load(settings: &Settings, bucket: &Bucket<'_>) -> anyhow::Result<Self> {
+        for key in [&settings.hotspot_allowlist, &settings.hotspot_denylist]
+            .into_iter()
+            .flatten()
+        {
+            source::ensure_allowed(key, settings.allow_local_files)?;
+        }
+        let allow = match &settings.hotspot_allowlist {
+            Some(key) => Some(read_keys(bucket, key).await?),
+            None => None,
+        };
+        let deny = match &settings.hotspot_denylist {
+            Some(key) => read_keys(bucket, key).await?,
+            None => HashSet::new(),
+        };
+        Ok(Self::new(allow, deny))
+    }
+
+    pub fn new(allow: Option<HashSet<String>>, deny: HashSet<String>) -> Self {
+        Self { allow, deny }
+    }
+
+    pub fn allows(&self, hotspot_key: &PublicKey) -> bool {
+        let hotspot_key = hotspot_key.to_string();
+        let allowed = self
+            .allow
+            .as_ref()
+            .is_none_or(|allow| allow.contains(&hotspot_key));
+        allowed && !self.deny.contains(&hotspot_key)
+    }
+}
+
+/// Reads a list of base58 hotspot keys, one per line.
+async fn read_keys(bucket: &Bucket<'_>, key: &str) -> anyhow::Result<HashSet<String>> {
+    let body = String::from_utf8(bucket.read(key).await?)?;
+    body.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            PublicKey::from_str(line)
+                .map(|hotspot_key| hotspot_key.to_string())
+                .map_err(|err| anyhow!("Invalid hotspot key {line:?} in {key}: {err}"))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use helium_crypto::{KeyTag, KeyType, Keypair, Network};
+    use rand::rngs::OsRng;
+
+    pub fn hotspot() -> PublicKey {
+        let tag = KeyTag {
+            network: Network::MainNet,
+            key_type: KeyType::Ed25519,
+        };
+        Keypair::generate(tag, &mut OsRng).public_key().clone()
+    }
+
+    #[test]
+    fn allows_every_hotspot_by_default() {
+        assert!(HotspotFilter::default().allows(&hotspot()));
+    }
+
+    #[test]
+    fn applies_allowlist_then_denylist() {
+        let (listed, unlisted, denied) = (hotspot(), hotspot(), hotspot());
+        let allow = [listed.to_string(), denied.to_string()].into();
+        let filter = HotspotFilter::new(Some(allow), [denied.to_string()].into());
+
+        assert!(filter.allows(&listed));
+        assert!(!filter.allows(&unlisted));
+        assert!(!filter.allows(&denied));
+    }
+}
@@ -0,0 +1,331 @@
+use crate::{
+    anonymize::Anonymizer,
+    filter::HotspotFilter,
+    quarantine::Quarantine,
+    rewards::{IotReward, MobileReward, Provenance, Reward, RowWriter},
+    source::{self, InputSource},
+    summary::Summary,
+};
+use anyhow::anyhow;
+use file_store::{BytesMutStream, FileType};
+use futures::{Future, StreamExt};
+use helium_proto::Message;
+use lambda_runtime::Error;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+/// Decodes oracle files and writes their rows, usually to a shared transaction.
+pub struct Ingestor<'a> {
+    pub source: &'a dyn InputSource,
+    pub quarantine: Quarantine<'a>,
+    pub filter: HotspotFilter,
+    pub anonymizer: Option<Anonymizer>,
+    pub batch_size: usize,
+    /// Keep the messages read before a truncated or corrupt part of a file
+    /// instead of failing it.
+    pub salvage: bool,
+    /// Recorded as every row's ingest_batch_id.
+    pub batch_id: &'a str,
+    /// How long reading a file may take before it fails.
+    pub file_timeout: Option<Duration>,
+}
+
+impl Ingestor<'_> {
+    #[tracing::instrument(skip_all, fields(key = %key))]
+    pub async fn ingest_file<W: RowWriter>(
+        &mut self,
+        key: &str,
+        writer: &mut W,
+        summary: &mut Summary,
+    ) -> Result<(), Error> {
+        let file_type = source::file_type(key)?;
+        let deadline = self.file_timeout.map(|limit| Instant::now() + limit);
+        let opened = self.source.open(key).instrument(tracing::info_span!("get"));
+        let file_stream = within(deadline, key, opened).await??;
+
+        match file_type {
+            FileType::RadioRewardShare => {
+                self.ingest::<MobileReward, W>(file_stream, key, writer, summary, deadline)
+                    .await
+            }
+            FileType::GatewayRewardShare => {
+                self.ingest::<IotReward, W>(file_stream, key, writer, summary, deadline)
+                    .await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Decodes every message in the file, inserting rows `batch_size` at a time.
+    async fn ingest<R: Reward, W: RowWriter>(
+        &mut self,
+        mut file_stream: BytesMutStream,
+        key: &str,
+        writer: &mut W,
+        summary: &mut Summary,
+        deadline: Option<Instant>,
+    ) -> Result<(), Error> {
+        let provenance = Provenance {
+            source_key: key,
+            batch_id: self.batch_id,
+        };
+        let mut rows = Vec::with_capacity(self.batch_size);
+        let mut index = 0;
+        // bytes into the decompressed file, counting each message's 4 byte
+        // length prefix, so a corrupt part can be found with a hex dump
+        let mut offset = 0;
+        let mut partial = false;
+        while let Some(result) = within(deadline, key, file_stream.next()).await? {
+            let msg = match result {
+                Ok(msg) => msg,
+                // a dropped connection says nothing about the file, so it
+                // fails it to be retried whole rather than salvaged
+                Err(err) if source::is_read_error(&err) => {
+                    return Err(anyhow!("Unable to read {key} after message {index}: {err}").into())
+                }
+                // gzip checks each member's CRC and length, so corruption
+                // surfaces as a stream error partway through
+                Err(err) if self.salvage => {
+                    tracing::warn!(
+                        key,
+                        after_message = index,
+                        offset,
+                        %err,
+                        "salvaging corrupt file"
+                    );
+                    summary.salvaged_keys.push(key.to_string());
+                    partial = true;
+                    break;
+                }
+                Err(err) => {
+                    return Err(anyhow!(
+                        "{key} is corrupt after message {index}, at byte {offset}: {err}"
+                    )
+                    .into())
+                }
+            };
+            index += 1;
+            offset += 4 + msg.len();
+            summary.messages_read += 1;
+            let proto = match R::Proto::decode(&msg[..]) {
+                Ok(proto) => proto,
+                Err(err) => {
+                    self.quarantine.record(key, index, &msg, err).await?;
+                    continue;
+                }
+            };
+            let row = R::from_proto(proto)?;
+            if !self.filter.allows(row.hotspot_key()) {
+                summary.rows_filtered += 1;
+                continue;
+            }
+            rows.push(row);
+            if rows.len() >= self.batch_size {
+                let batch = std::mem::take(&mut rows);
+                let written = writer
+                    .insert(batch, self.anonymizer.as_ref(), &provenance)
+                    .await?;
+                summary.add_rows(R::TABLE, written);
+            }
+        }
+        let written = writer
+            .insert(rows, self.anonymizer.as_ref(), &provenance)
+            .await?;
+        summary.add_rows(R::TABLE, written);
+        writer.mark_ingested(&provenance, partial).await?;
+
+        Ok(())
+    }
+}
+
+/// Awaits `future`, failing once the file's deadline has passed. Only reads
+/// are bounded: cancelling a query mid-flight would leave the transaction's
+/// connection unusable, so an insert in progress always finishes.
+async fn within<T>(
+    deadline: Option<Instant>,
+    key: &str,
+    future: impl Future<Output = T>,
+) -> Result<T, Error> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline, future)
+            .await
+            .map_err(|_| anyhow!("{key} exceeded FILE_TIMEOUT_SECS").into()),
+        None => Ok(future.await),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::ReadError;
+    use crate::{filter::tests::hotspot, source::MemoryBucket};
+    use futures::{future::BoxFuture, FutureExt};
+    use helium_crypto::PublicKey;
+    use helium_proto::services::poc_mobile::RadioRewardShare;
+    use std::io;
+
+    const KEY: &str = "radio_reward_share.1671148800000.gz";
+
+    /// Keeps the hotspot keys of each inserted batch.
+    #[derive(Default)]
+    struct MemoryWriter {
+        batches: Vec<Vec<String>>,
+        /// Each ingested key and whether it was partial.
+        ingested: Vec<(String, bool)>,
+    }
+
+    impl RowWriter for MemoryWriter {
+        fn insert<'a, R: Reward + 'a>(
+            &'a mut self,
+            rows: Vec<R>,
+            _anonymizer: Option<&'a Anonymizer>,
+            _provenance: &'a Provenance<'a>,
+        ) -> BoxFuture<'a, sqlx::Result<u64>> {
+            let written = rows.len() as u64;
+            if !rows.is_empty() {
+                let keys = rows.iter().map(|row| row.hotspot_key().to_string());
+                self.batches.push(keys.collect());
+            }
+            futures::future::ready(Ok(written)).boxed()
+        }
+
+        fn mark_ingested<'a>(
+            &'a mut self,
+            provenance: &'a Provenance<'a>,
+            partial: bool,
+        ) -> BoxFuture<'a, sqlx::Result<()>> {
+            self.ingested
+                .push((provenance.source_key.to_string(), partial));
+            futures::future::ready(Ok(())).boxed()
+        }
+    }
+
+    fn reward(hotspot_key: &PublicKey) -> RadioRewardShare {
+        RadioRewardShare {
+            hotspot_key: hotspot_key.to_vec(),
+            cbsd_id: "P27-SCE4255W2107CW5000014".to_string(),
+            amount: 10,
+            end_epoch: 1671148800,
+            ..Default::default()
+        }
+    }
+
+    fn ingestor(bucket: &MemoryBucket, filter: HotspotFilter, batch_size: usize) -> Ingestor<'_> {
+        Ingestor {
+            source: bucket,
+            quarantine: Quarantine::new(bucket, "quarantine/", 1),
+            filter,
+            anonymizer: None,
+            batch_size,
+            salvage: false,
+            batch_id: "batch",
+            file_timeout: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_rows_and_quarantines_bad_messages() {
+        let (first, second) = (hotspot(), hotspot());
+        let mut bucket = MemoryBucket::default();
+        bucket.insert_raw(
+            KEY,
+            vec![
+                reward(&first).encode_to_vec(),
+                vec![0xff],
+                reward(&second).encode_to_vec(),
+            ],
+        );
+        let (mut writer, mut summary) = (MemoryWriter::default(), Summary::default());
+
+        let mut ingestor = ingestor(&bucket, HotspotFilter::default(), 10);
+        ingestor
+            .ingest_file(KEY, &mut writer, &mut summary)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.messages_read, 3);
+        assert_eq!(summary.rows_written[MobileReward::TABLE], 2);
+        assert_eq!(ingestor.quarantine.errors(), 1);
+        assert_eq!(
+            writer.batches,
+            vec![vec![first.to_string(), second.to_string()]]
+        );
+        assert_eq!(writer.ingested, vec![(KEY.to_string(), false)]);
+        let puts = bucket.puts.lock().unwrap();
+        assert_eq!(*puts, vec![(format!("quarantine/{KEY}.2"), vec![0xff])]);
+    }
+
+    #[tokio::test]
+    async fn drops_filtered_hotspots() {
+        let (kept, denied) = (hotspot(), hotspot());
+        let mut bucket = MemoryBucket::default();
+        bucket.insert(KEY, &[reward(&denied), reward(&kept)]);
+        let (mut writer, mut summary) = (MemoryWriter::default(), Summary::default());
+
+        let filter = HotspotFilter::new(None, [denied.to_string()].into());
+        ingestor(&bucket, filter, 10)
+            .ingest_file(KEY, &mut writer, &mut summary)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.rows_filtered, 1);
+        assert_eq!(writer.batches, vec![vec![kept.to_string()]]);
+    }
+
+    #[tokio::test]
+    async fn inserts_in_batches() {
+        let rewards: Vec<_> = (0..5).map(|_| reward(&hotspot())).collect();
+        let mut bucket = MemoryBucket::default();
+        bucket.insert(KEY, &rewards);
+        let (mut writer, mut summary) = (MemoryWriter::default(), Summary::default());
+
+        ingestor(&bucket, HotspotFilter::default(), 2)
+            .ingest_file(KEY, &mut writer, &mut summary)
+            .await
+            .unwrap();
+
+        let sizes: Vec<_> = writer.batches.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+        assert_eq!(summary.rows_written[MobileReward::TABLE], 5);
+    }
+
+    #[tokio::test]
+    async fn salvages_messages_before_corruption() {
+        let mut bucket = MemoryBucket::default();
+        bucket.insert(KEY, &[reward(&hotspot())]);
+        bucket.fail_after(KEY, || {
+            io::Error::new(io::ErrorKind::InvalidData, "bad crc")
+        });
+        let (mut writer, mut summary) = (MemoryWriter::default(), Summary::default());
+
+        let mut ingestor = ingestor(&bucket, HotspotFilter::default(), 10);
+        ingestor.salvage = true;
+        ingestor
+            .ingest_file(KEY, &mut writer, &mut summary)
+            .await
+            .unwrap();
+
+        assert_eq!(summary.rows_written[MobileReward::TABLE], 1);
+        assert_eq!(summary.salvaged_keys, vec![KEY.to_string()]);
+        assert_eq!(writer.ingested, vec![(KEY.to_string(), true)]);
+    }
+
+    #[tokio::test]
+    async fn fails_on_read_errors_even_when_salvaging() {
+        let mut bucket = MemoryBucket::default();
+        bucket.insert(KEY, &[reward(&hotspot())]);
+        bucket.fail_after(KEY, || {
+            io::Error::other(ReadError("connection reset".into()))
+        });
+        let (mut writer, mut summary) = (MemoryWriter::default(), Summary::default());
+
+        let mut ingestor = ingestor(&bucket, HotspotFilter::default(), 10);
+        ingestor.salvage = true;
+        let result = ingestor.ingest_file(KEY, &mut writer, &mut summary).await;
+
+        assert!(result.is_err());
+        assert!(summary.salvaged_keys.is_empty());
+        assert!(writer.ingested.is_empty());
+    }
+}
@@ -1,120 +1,407 @@
 use anyhow::anyhow;
-use chrono::{TimeZone, Utc};
-use file_store::{FileStore, FileType, Settings};
-use futures::StreamExt;
-use helium_crypto::PublicKey;
-use helium_proto::{
-    services::{poc_lora::GatewayRewardShare, poc_mobile::RadioRewardShare},
-    Message,
+use aws_config::retry::RetryConfig;
+use aws_sdk_s3::Region;
+use lambda_runtime::{service_fn, Context, Error, LambdaEvent};
+use serde::Serialize;
+use sqlx::{
+    postgres::{PgPool, PgPoolOptions},
+    Connection,
 };
-use lambda_runtime::{service_fn, Error, LambdaEvent};
-use serde_json::{json, Value};
-use sqlx::postgres::PgPoolOptions;
-use std::{env, str::FromStr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+
+mod anonymize;
+mod batch;
+mod event;
+mod filter;
+mod ingest;
+mod manifest;
+mod metrics;
+mod notify;
+mod quarantine;
+mod report;
+mod retry;
+mod rewards;
+mod settings;
+mod source;
+mod summary;
+mod sweep;
+mod telemetry;
+
+use anonymize::Anonymizer;
+use batch::{BatchResponse, ResultCode};
+use event::{Event, Target};
+use filter::HotspotFilter;
+use ingest::Ingestor;
+use quarantine::Quarantine;
+use retry::RetryPolicy;
+use settings::{FailurePolicy, Settings};
+use source::{Bucket, ObjectSink};
+use summary::{FailedFile, PlannedFile, Summary};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let handler = service_fn(handler);
+    telemetry::init()?;
+    let settings = Settings::load().await?;
+    // kept for the life of the lambda, reporting to Sentry only when a DSN is set
+    let _sentry = settings.sentry_dsn.as_deref().map(|dsn| {
+        sentry::init((
+            dsn,
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                ..Default::default()
+            },
+        ))
+    });
+
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_lazy(&settings.database_url)
+        .map_err(|err| anyhow!("Invalid DATABASE_URL: {err}"))?;
+    // fail at startup on an unreachable database rather than on the first event
+    if !settings.dry_run {
+        sqlx::migrate!()
+            .run(&pool)
+            .await
+            .map_err(|err| anyhow!("Unable to migrate the DATABASE_URL database: {err}"))?;
+    }
+
+    let (settings, pool) = (&settings, &pool);
+    let handler = service_fn(move |event| async move {
+        let response = handler(settings, pool, event).await;
+        // after the handler's span has closed, so it's exported too
+        telemetry::flush().await;
+        response
+    });
     lambda_runtime::run(handler).await?;
     Ok(())
 }
 
-async fn handler(event: LambdaEvent<Value>) -> Result<Value, Error> {
-    let db_url = env::var("DATABASE_URL");
-    if db_url.is_err() {
-        return Err(anyhow!("DATABASE_URL must be set in lambda env variable.").into());
+/// What an invocation returns, depending on how it was invoked.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Response {
+    Summary(Summary),
+    Batch(BatchResponse),
+}
+
+#[tracing::instrument(skip_all, fields(request_id = %event.context.request_id))]
+async fn handler(
+    settings: &Settings,
+    pool: &PgPool,
+    event: LambdaEvent<Event>,
+) -> Result<Response, Error> {
+    let (event, context) = event.into_parts();
+
+    let target = match event {
+        Event::S3(event) => {
+            let target = match event::target(event)? {
+                Some(target) => target,
+                None => return Ok(Response::Summary(Summary::default())),
+            };
+            let summary = ingest_target(settings, pool, &context, target).await?;
+            // S3 invokes asynchronously, so nobody reads the response; failing
+            // has Lambda retry the event, and rows already written are skipped
+            if !summary.unprocessed_keys.is_empty() {
+                return Err(anyhow!(
+                    "Invocation deadline reached with {} keys unprocessed.",
+                    summary.unprocessed_keys.len()
+                )
+                .into());
+            }
+            return Ok(Response::Summary(summary));
+        }
+        Event::Sweep { sweep } => sweep::target(settings, pool, sweep).await?,
+        Event::Batch(job) => {
+            let mut results = Vec::with_capacity(job.tasks.len());
+            for task in &job.tasks {
+                let result = match task.target() {
+                    Ok(target) => {
+                        task.result(ingest_target(settings, pool, &context, target).await)
+                    }
+                    Err(err) => task.invalid(err),
+                };
+                results.push(result);
+            }
+            return Ok(Response::Batch(BatchResponse {
+                invocation_schema_version: job.invocation_schema_version,
+                treat_missing_keys_as: ResultCode::PermanentFailure,
+                invocation_id: job.invocation_id,
+                results,
+            }));
+        }
+    };
+    Ok(Response::Summary(
+        ingest_target(settings, pool, &context, target).await?,
+    ))
+}
+
+/// Ingests the target's keys in one transaction.
+async fn ingest_target(
+    settings: &Settings,
+    pool: &PgPool,
+    context: &Context,
+    target: Target,
+) -> Result<Summary, Error> {
+    let started = Instant::now();
+    let mut summary = Summary::default();
+    let result = ingest_keys(settings, pool, context, target, &mut summary).await;
+    // failed invocations are what alarms watch for, so they emit metrics too
+    if !settings.dry_run {
+        metrics::emit(&settings.metrics_namespace, &summary, started.elapsed());
     }
-    let db_url = db_url.unwrap();
+    report::flush().await;
+    result.map(|()| summary)
+}
 
-    let pool = PgPoolOptions::new()
-        .max_connections(5)
-        .connect(db_url.as_str())
-        .await?;
-    sqlx::migrate!().run(&pool).await?;
+/// Resolves the target's keys and ingests them, recording progress in
+/// `summary` even when the invocation fails.
+async fn ingest_keys(
+    settings: &Settings,
+    pool: &PgPool,
+    context: &Context,
+    target: Target,
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    let deadline = UNIX_EPOCH + Duration::from_millis(context.deadline);
 
-    let (event, _context) = event.into_parts();
+    let (bucket, region) = (target.bucket.as_str(), target.region.as_str());
 
-    // guard against empty records
-    if event["Records"].is_null() {
-        return Err(anyhow!("Event records are unexpectedly null.").into());
+    let (event_keys, ignored): (Vec<_>, Vec<_>) = target
+        .keys
+        .into_iter()
+        .partition(|key| settings.accepts_event(key));
+    for key in &ignored {
+        tracing::info!(
+            key,
+            "ignoring event, key does not match KEY_PREFIXES/KEY_SUFFIXES"
+        );
+    }
+    summary.skipped_keys = ignored;
+    if event_keys.is_empty() {
+        return Ok(());
     }
 
-    let record = &event["Records"][0];
-    let bucket = record["s3"]["bucket"]["name"]
-        .as_str()
-        .unwrap_or("bucket not found");
-    let key = record["s3"]["object"]["key"]
-        .as_str()
-        .unwrap_or("key not found");
-    let region = record["awsRegion"].as_str().unwrap_or("region not found");
-
-    let settings = &Settings {
-        region: region.to_string(),
-        bucket: bucket.to_string(),
-        endpoint: None,
+    let aws_config = aws_config::from_env()
+        .region(Region::new(region.to_string()))
+        .load()
+        .await;
+    // RetryPolicy is the only retry layer for this client
+    let s3 = aws_sdk_s3::Client::from_conf(
+        source::client_config(settings, &aws_config)
+            .retry_config(RetryConfig::disabled())
+            .build(),
+    );
+
+    let retry = RetryPolicy::new(settings.retry_attempts, settings.retry_base_delay);
+    let source_bucket = Bucket {
+        client: &s3,
+        retry: &retry,
+        bucket,
+        request_payer: settings.request_payer(),
     };
 
-    let prefix = key.split('.').next().unwrap_or("");
-    let file_type = FileType::from_str(prefix)?;
-    let store = FileStore::from_settings(settings).await?;
-    let mut file_stream = store.get(key).await?;
-
-    println!("bucket is {}", bucket);
-    println!("key is {}", key);
-    println!("region is {}", region);
-
-    let mut count = 0;
-    while let Some(result) = file_stream.next().await {
-        let msg = result?;
-        count += 1;
-        match file_type {
-            FileType::RadioRewardShare => {
-                let reward = RadioRewardShare::decode(msg)?;
-                let end_epoch = Utc.timestamp_opt(reward.end_epoch as i64, 0);
-                if let chrono::LocalResult::Single(end_epoch) = end_epoch {
-                    sqlx::query(
-                        r#"
-                        INSERT INTO mobile_poc_rewards (amount, epoch_end, hotspot_key, cbsd_id)
-                        VALUES ($1, $2, $3, $4)
-                        ON CONFLICT
-                        DO NOTHING
-                        "#,
-                    )
-                    .bind(reward.amount as i64)
-                    .bind(end_epoch)
-                    .bind(PublicKey::try_from(reward.hotspot_key)?)
-                    .bind(reward.cbsd_id)
-                    .execute(&pool)
-                    .await?;
-                } else {
-                    return Err(anyhow!("Unexpected end_epoch: {end_epoch:?}").into());
-                }
+    tracing::info!(bucket, region, keys = ?event_keys, "received event");
+
+    let mut keys = Vec::new();
+    for key in &event_keys {
+        source::ensure_allowed(key, settings.allow_local_files)?;
+        // a manifest batches many small files into a single invocation
+        if manifest::is_manifest(key) {
+            let manifest = manifest::fetch(&source_bucket, key)
+                .instrument(tracing::info_span!("manifest", key))
+                .await?;
+            keys.extend(manifest.keys);
+        } else {
+            keys.extend(source::expand(key)?);
+        }
+    }
+
+    for key in &keys {
+        source::ensure_allowed(key, settings.allow_local_files)?;
+    }
+
+    if settings.dry_run {
+        plan(settings, &source_bucket, &keys, summary).await?;
+        return Ok(());
+    }
+
+    let mut ingestor = Ingestor {
+        source: &source_bucket,
+        quarantine: Quarantine::new(
+            &source_bucket,
+            &settings.quarantine_prefix,
+            settings.max_decode_errors,
+        ),
+        filter: HotspotFilter::load(settings, &source_bucket).await?,
+        anonymizer: match &settings.anonymize_salt_secret {
+            Some(secret_id) => Some(Anonymizer::load(&aws_config, secret_id).await?),
+            None => None,
+        },
+        batch_size: settings.insert_batch_size,
+        salvage: settings.salvage_corrupt_files,
+        batch_id: &context.request_id,
+        file_timeout: settings.file_timeout,
+    };
+
+    let result = ingest_files(
+        settings,
+        pool,
+        &mut ingestor,
+        &source_bucket,
+        deadline,
+        &keys,
+        summary,
+    )
+    .await;
+    summary.decode_errors = ingestor.quarantine.errors();
+    summary.retries = retry.retries();
+    result?;
+
+    tracing::info!(?summary, "invocation complete");
+    // the rows are already committed, so a lost notification doesn't fail the invocation
+    if let Err(err) = notify::send(
+        settings,
+        &aws_config,
+        &retry,
+        &context.request_id,
+        bucket,
+        summary,
+    )
+    .await
+    {
+        tracing::error!(%err, "unable to send completion notification");
+    }
+    Ok(())
+}
+
+/// Ingests every key in one transaction, committing unless the invocation
+/// fails.
+async fn ingest_files(
+    settings: &Settings,
+    pool: &PgPool,
+    ingestor: &mut Ingestor<'_>,
+    sink: &dyn ObjectSink,
+    deadline: SystemTime,
+    keys: &[String],
+    summary: &mut Summary,
+) -> Result<(), Error> {
+    // all files share one transaction so a batch is written all or nothing
+    let mut tx = pool.begin().await?;
+    // under fail_fast, the failure that stopped the batch, returned once reported
+    let mut fatal = None;
+    for (i, key) in keys.iter().enumerate() {
+        // leave enough time to commit what's done rather than being cut off mid-write
+        let remaining = deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or_default();
+        // the first key is always attempted so a short timeout can't skip
+        // every file
+        if i > 0 && remaining < settings.deadline_margin {
+            tracing::warn!(key, ?remaining, "deadline near, stopping before key");
+            summary.unprocessed_keys = keys[i..].to_vec();
+            break;
+        }
+        if !settings.ingests(key) {
+            tracing::info!(key, "skipping key, file type is not in FILE_TYPES");
+            summary.skipped_keys.push(key.clone());
+            continue;
+        }
+        // each file gets a savepoint so one that fails is rolled back alone
+        let file_started = Instant::now();
+        let mut file_tx = tx.begin().await?;
+        let mut file_summary = Summary::default();
+        match ingestor
+            .ingest_file(key, &mut file_tx, &mut file_summary)
+            .await
+        {
+            Ok(()) => {
+                file_tx.commit().await?;
+                tracing::info!(
+                    key,
+                    file_type = source::prefix(key),
+                    rows = file_summary.rows_written.values().sum::<u64>(),
+                    duration_ms = file_started.elapsed().as_millis() as u64,
+                    "ingested file"
+                );
+                summary.merge(file_summary);
+                summary.files_processed += 1;
             }
-            FileType::GatewayRewardShare => {
-                let reward = GatewayRewardShare::decode(msg)?;
-                let end_period = Utc.timestamp_opt(reward.end_period as i64, 0);
-                if let chrono::LocalResult::Single(end_period) = end_period {
-                    sqlx::query(
-                        r#"
-                        INSERT INTO iot_poc_rewards (beacon_amount, witness_amount, epoch_end, hotspot_key)
-                        VALUES ($1, $2, $3, $4)
-                        ON CONFLICT
-                        DO NOTHING
-                        "#
-                        , )
-                        .bind(reward.beacon_amount as i64)
-                        .bind(reward.witness_amount as i64)
-                        .bind(end_period)
-                        .bind(PublicKey::try_from(reward.hotspot_key)?)
-                        .execute(&pool).await?;
-                } else {
-                    return Err(anyhow!("Unexpected end_epoch: {end_period:?}").into());
+            Err(err) => {
+                report::capture(key, &err.to_string());
+                summary.failed.push(FailedFile {
+                    key: key.clone(),
+                    error: err.to_string(),
+                });
+                if settings.failure_policy == FailurePolicy::FailFast {
+                    fatal = Some(err);
+                    break;
                 }
+                tracing::error!(key, %err, "file failed, rolling it back");
+                file_tx.rollback().await?;
             }
-            _ => (),
         }
     }
+    if !summary.failed.is_empty() {
+        tracing::warn!(
+            files_processed = summary.files_processed,
+            failed = summary.failed.len(),
+            "some files failed"
+        );
+        let reported = report::write(
+            sink,
+            &settings.failure_report_prefix,
+            ingestor.batch_id,
+            &summary.failed,
+        )
+        .await;
+        if let Some(err) = fatal {
+            if let Err(report_err) = reported {
+                tracing::error!(%report_err, "unable to write failure report");
+            }
+            return Err(err);
+        }
+        reported?;
 
-    let message = format!("{count} rows of {prefix} processed.");
-    Ok(json!({ "message": message }))
+        // too many failures fail the whole batch, rolling back the files that succeeded
+        let attempted = summary.files_processed + summary.failed.len();
+        if summary.failed.len() as f64 > settings.max_failure_rate * attempted as f64 {
+            return Err(anyhow!(
+                "{} of {attempted} files failed, exceeding MAX_FAILURE_RATE {}",
+                summary.failed.len(),
+                settings.max_failure_rate
+            )
+            .into());
+        }
+    }
+    tx.commit()
+        .instrument(tracing::info_span!("commit"))
+        .await?;
+    Ok(())
+}
+
+/// Lists the files an invocation would ingest and the table each would be
+/// written to, without downloading them.
+async fn plan(
+    settings: &Settings,
+    source_bucket: &Bucket<'_>,
+    keys: &[String],
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    for key in keys {
+        let table = match source::file_type(key) {
+            Ok(file_type) if settings.ingests(key) => rewards::table(&file_type),
+            _ => None,
+        };
+        match table {
+            Some(table) => summary.plan.push(PlannedFile {
+                key: key.clone(),
+                size: source_bucket.size(key).await?,
+                table,
+            }),
+            None => summary.skipped_keys.push(key.clone()),
+        }
+    }
+    tracing::info!(?summary, "dry run complete");
+    Ok(())
 }
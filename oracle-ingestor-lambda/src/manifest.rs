@@ -0,0 +1,21 @@
+use crate::source::Bucket;
+use serde::Deserialize;
+
+/// Keys ending in this suffix are treated as manifests rather than oracle files.
+pub const MANIFEST_SUFFIX: &str = ".manifest.json";
+
+/// A manifest lists oracle files, relative to the same bucket, that should be
+/// ingested together in a single invocation.
+#[derive(Debug, Deserialize)]
+pub struct Manifest {
+    pub keys: Vec<String>,
+}
+
+pub fn is_manifest(key: &str) -> bool {
+    key.ends_with(MANIFEST_SUFFIX)
+}
+
+pub async fn fetch(bucket: &Bucket<'_>, key: &str) -> anyhow::Result<Manifest> {
+    let body = bucket.read(key).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
@@ -0,0 +1,36 @@
+use crate::summary::Summary;
+use chrono::Utc;
+use serde_json::json;
+use std::time::Duration;
+
+/// Prints the invocation's metrics in CloudWatch Embedded Metric Format, which
+/// CloudWatch Logs turns into metrics without any extra API calls.
+pub fn emit(namespace: &str, summary: &Summary, elapsed: Duration) {
+    let rows_written: u64 = summary.rows_written.values().sum();
+    let metrics = json!({
+        "_aws": {
+            "Timestamp": Utc::now().timestamp_millis(),
+            "CloudWatchMetrics": [{
+                "Namespace": namespace,
+                "Dimensions": [[]],
+                "Metrics": [
+                    { "Name": "FilesProcessed", "Unit": "Count" },
+                    { "Name": "FilesFailed", "Unit": "Count" },
+                    { "Name": "MessagesRead", "Unit": "Count" },
+                    { "Name": "RowsWritten", "Unit": "Count" },
+                    { "Name": "DecodeErrors", "Unit": "Count" },
+                    { "Name": "Retries", "Unit": "Count" },
+                    { "Name": "Duration", "Unit": "Milliseconds" },
+                ],
+            }],
+        },
+        "FilesProcessed": summary.files_processed,
+        "FilesFailed": summary.failed.len(),
+        "MessagesRead": summary.messages_read,
+        "RowsWritten": rows_written,
+        "DecodeErrors": summary.decode_errors,
+        "Retries": summary.retries,
+        "Duration": elapsed.as_millis() as u64,
+    });
+    println!("{metrics}");
+}
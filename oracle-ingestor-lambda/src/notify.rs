@@ -0,0 +1,65 @@
+use crate::{retry::RetryPolicy, settings::Settings, summary::Summary};
+use aws_config::retry::RetryConfig;
+use aws_types::SdkConfig;
+use serde::Serialize;
+
+/// Published once an invocation commits, so downstream jobs can start as soon
+/// as the data they need is in.
+#[derive(Debug, Serialize)]
+struct Notification<'a> {
+    batch_id: &'a str,
+    bucket: &'a str,
+    #[serde(flatten)]
+    summary: &'a Summary,
+}
+
+/// Publishes the summary to `NOTIFY_SNS_TOPIC_ARN` and posts it to
+/// `NOTIFY_WEBHOOK_URL`, whichever are set.
+pub async fn send(
+    settings: &Settings,
+    config: &SdkConfig,
+    retry: &RetryPolicy,
+    batch_id: &str,
+    bucket: &str,
+    summary: &Summary,
+) -> anyhow::Result<()> {
+    let notification = Notification {
+        batch_id,
+        bucket,
+        summary,
+    };
+
+    if let Some(topic_arn) = &settings.notify_sns_topic_arn {
+        // RetryPolicy is the only retry layer for this client
+        let client = aws_sdk_sns::Client::from_conf(
+            aws_sdk_sns::config::Builder::from(config)
+                .retry_config(RetryConfig::disabled())
+                .build(),
+        );
+        let message = serde_json::to_string(&notification)?;
+        retry
+            .run("sns publish", || {
+                client
+                    .publish()
+                    .topic_arn(topic_arn)
+                    .message(&message)
+                    .send()
+            })
+            .await?;
+    }
+
+    if let Some(url) = &settings.notify_webhook_url {
+        let client = reqwest::Client::new();
+        retry
+            .run("webhook post", || async {
+                client
+                    .post(url)
+                    .json(&notification)
+                    .send()
+                    .await?
+                    .error_for_status()
+            })
+            .await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use crate::source::ObjectSink;
+use anyhow::anyhow;
+use helium_proto::DecodeError;
+
+/// Tracks messages that fail to decode, copying their raw bytes aside so the
+/// rest of the file can still be ingested.
+pub struct Quarantine<'a> {
+    sink: &'a dyn ObjectSink,
+    prefix: &'a str,
+    max_errors: usize,
+    errors: usize,
+}
+
+impl<'a> Quarantine<'a> {
+    pub fn new(sink: &'a dyn ObjectSink, prefix: &'a str, max_errors: usize) -> Self {
+        Self {
+            sink,
+            prefix,
+            max_errors,
+            errors: 0,
+        }
+    }
+
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+
+    /// Writes the raw message to `<prefix><key>.<index>` and fails once more
+    /// than `max_errors` messages have been quarantined.
+    pub async fn record(
+        &mut self,
+        key: &str,
+        index: usize,
+        msg: &[u8],
+        err: DecodeError,
+    ) -> anyhow::Result<()> {
+        self.errors += 1;
+        if self.errors > self.max_errors {
+            return Err(anyhow!(
+                "Message {index} of {key} failed to decode ({err}), exceeding the maximum of {} decode errors.",
+                self.max_errors
+            ));
+        }
+
+        let quarantine_key = format!("{}{key}.{index}", self.prefix);
+        tracing::warn!(key, index, quarantine_key, %err, "quarantining message");
+        self.sink.put(&quarantine_key, msg.to_vec()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::MemoryBucket;
+
+    #[tokio::test]
+    async fn writes_messages_until_max_errors() {
+        let bucket = MemoryBucket::default();
+        let mut quarantine = Quarantine::new(&bucket, "quarantine/", 1);
+
+        quarantine
+            .record("file.gz", 3, b"bad", DecodeError::new("bad"))
+            .await
+            .unwrap();
+        assert!(quarantine
+            .record("file.gz", 4, b"worse", DecodeError::new("worse"))
+            .await
+            .is_err());
+
+        assert_eq!(quarantine.errors(), 2);
+        let puts = bucket.puts.lock().unwrap();
+        assert_eq!(
+            *puts,
+            vec![("quarantine/file.gz.3".to_string(), b"bad".to_vec())]
+        );
+    }
+}
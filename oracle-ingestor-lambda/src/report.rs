@@ -0,0 +1,55 @@
+use crate::{
+    source::{self, ObjectSink},
+    summary::FailedFile,
+};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Longest flushing failure reports may hold up the invocation before the
+/// lambda freezes.
+const SENTRY_FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    batch_id: &'a str,
+    failed: &'a [FailedFile],
+}
+
+/// Writes the invocation's failed files, as JSON, to `<prefix><batch_id>.json`
+/// so they can be retried or investigated without digging through logs.
+pub async fn write(
+    sink: &dyn ObjectSink,
+    prefix: &str,
+    batch_id: &str,
+    failed: &[FailedFile],
+) -> anyhow::Result<()> {
+    let report_key = format!("{prefix}{batch_id}.json");
+    let body = serde_json::to_vec(&Report { batch_id, failed })?;
+    tracing::info!(report_key, "writing failure report");
+    sink.put(&report_key, body).await?;
+    Ok(())
+}
+
+/// Sends a file's failure to Sentry, tagged with its key and file type. Does
+/// nothing unless `SENTRY_DSN` is set.
+/// Events are only queued; call [`flush`] before the invocation returns.
+pub fn capture(key: &str, error: &str) {
+    sentry::with_scope(
+        |scope| {
+            scope.set_tag("key", key);
+            scope.set_tag("file_type", source::prefix(key));
+        },
+        || sentry::capture_message(error, sentry::Level::Error),
+    );
+}
+
+/// Sends the invocation's queued Sentry events before the lambda freezes.
+/// The flush blocks, so it runs off the async workers.
+pub async fn flush() {
+    if let Some(client) = sentry::Hub::current().client() {
+        let flushed = tokio::task::spawn_blocking(move || client.flush(Some(SENTRY_FLUSH_TIMEOUT)));
+        if !matches!(flushed.await, Ok(true)) {
+            tracing::warn!("unable to flush Sentry events");
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use aws_smithy_http::result::SdkError;
+use aws_smithy_types::retry::{ErrorKind, ProvideErrorKind};
+use rand::Rng;
+use std::{
+    fmt::Display,
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+/// Retries transient failures, such as S3 `SlowDown` throttling, with
+/// exponential backoff and full jitter. Clients it wraps should have the SDK's
+/// own retries disabled so attempts aren't multiplied.
+#[derive(Debug)]
+pub struct RetryPolicy {
+    attempts: u32,
+    base_delay: Duration,
+    retries: AtomicUsize,
+}
+
+impl RetryPolicy {
+    pub fn new(attempts: u32, base_delay: Duration) -> Self {
+        Self {
+            attempts: attempts.max(1),
+            base_delay,
+            retries: AtomicUsize::new(0),
+        }
+    }
+
+    /// Total number of retries made through this policy.
+    pub fn retries(&self) -> usize {
+        self.retries.load(Ordering::Relaxed)
+    }
+
+    pub async fn run<T, E, F, Fut>(&self, what: &str, mut f: F) -> Result<T, E>
+    where
+        E: Display + Transient,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f().await {
+                Err(err) if attempt < self.attempts && err.is_transient() => {
+                    let delay = self.delay(attempt);
+                    tracing::warn!(what, attempt, ?delay, %err, "request failed, retrying");
+                    self.retries.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let max = self.base_delay.saturating_mul(1 << (attempt - 1).min(16));
+        rand::thread_rng().gen_range(Duration::ZERO..=max)
+    }
+}
+
+/// Whether an error is worth retrying: throttling, server errors and
+/// timeouts. Anything else, such as a missing key or denied access, fails
+/// straight away.
+pub trait Transient {
+    fn is_transient(&self) -> bool;
+}
+
+impl<E: ProvideErrorKind> Transient for SdkError<E> {
+    fn is_transient(&self) -> bool {
+        match self {
+            SdkError::ConstructionFailure(_) => false,
+            SdkError::TimeoutError(_) => true,
+            SdkError::DispatchFailure(err) => err.is_io() || err.is_timeout(),
+            // the connection dropped while the body was being read
+            SdkError::ResponseError { .. } => true,
+            SdkError::ServiceError { err, raw } => {
+                raw.http().status().is_server_error()
+                    || matches!(
+                        err.retryable_error_kind(),
+                        Some(
+                            ErrorKind::ThrottlingError
+                                | ErrorKind::TransientError
+                                | ErrorKind::ServerError
+                        )
+                    )
+                    || matches!(
+                        err.code(),
+                        Some("SlowDown" | "Throttling" | "ThrottlingException")
+                    )
+            }
+        }
+    }
+}
+
+impl Transient for reqwest::Error {
+    fn is_transient(&self) -> bool {
+        self.is_timeout()
+            || self.is_connect()
+            || self.status().is_some_and(|status| {
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+            })
+    }
+}
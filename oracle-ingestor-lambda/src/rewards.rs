@@ -0,0 +1,213 @@
+use crate::anonymize::{self, Anonymizer};
+use anyhow::anyhow;
+use chrono::{DateTime, TimeZone, Utc};
+use file_store::FileType;
+use futures::{future::BoxFuture, FutureExt};
+use helium_crypto::PublicKey;
+use helium_proto::{
+    services::{poc_lora::GatewayRewardShare, poc_mobile::RadioRewardShare},
+    Message,
+};
+use sqlx::{query_builder::Separated, Postgres, QueryBuilder, Transaction};
+
+/// A row decoded from an oracle reward share file, along with the table it is
+/// inserted into.
+pub trait Reward: Sized + Send {
+    type Proto: Message + Default;
+
+    const TABLE: &'static str;
+    const COLUMNS: &'static str;
+
+    fn from_proto(proto: Self::Proto) -> anyhow::Result<Self>;
+
+    fn hotspot_key(&self) -> &PublicKey;
+
+    /// Binds this row's values, in `COLUMNS` order.
+    fn bind(
+        self,
+        row: &mut Separated<'_, '_, Postgres, &'static str>,
+        anonymizer: Option<&Anonymizer>,
+    );
+}
+
+pub struct MobileReward {
+    pub amount: i64,
+    pub epoch_end: DateTime<Utc>,
+    pub hotspot_key: PublicKey,
+    pub cbsd_id: String,
+}
+
+impl Reward for MobileReward {
+    type Proto = RadioRewardShare;
+
+    const TABLE: &'static str = "mobile_poc_rewards";
+    const COLUMNS: &'static str = "amount, epoch_end, hotspot_key, cbsd_id";
+
+    fn from_proto(reward: RadioRewardShare) -> anyhow::Result<Self> {
+        Ok(Self {
+            amount: reward.amount as i64,
+            epoch_end: timestamp(reward.end_epoch)?,
+            hotspot_key: PublicKey::try_from(reward.hotspot_key)?,
+            cbsd_id: reward.cbsd_id,
+        })
+    }
+
+    fn hotspot_key(&self) -> &PublicKey {
+        &self.hotspot_key
+    }
+
+    fn bind(
+        self,
+        row: &mut Separated<'_, '_, Postgres, &'static str>,
+        anonymizer: Option<&Anonymizer>,
+    ) {
+        row.push_bind(self.amount)
+            .push_bind(self.epoch_end)
+            .push_bind(anonymize::hotspot_key(&self.hotspot_key, anonymizer))
+            .push_bind(self.cbsd_id);
+    }
+}
+
+pub struct IotReward {
+    pub beacon_amount: i64,
+    pub witness_amount: i64,
+    pub epoch_end: DateTime<Utc>,
+    pub hotspot_key: PublicKey,
+}
+
+impl Reward for IotReward {
+    type Proto = GatewayRewardShare;
+
+    const TABLE: &'static str = "iot_poc_rewards";
+    const COLUMNS: &'static str = "beacon_amount, witness_amount, epoch_end, hotspot_key";
+
+    fn from_proto(reward: GatewayRewardShare) -> anyhow::Result<Self> {
+        Ok(Self {
+            beacon_amount: reward.beacon_amount as i64,
+            witness_amount: reward.witness_amount as i64,
+            epoch_end: timestamp(reward.end_period)?,
+            hotspot_key: PublicKey::try_from(reward.hotspot_key)?,
+        })
+    }
+
+    fn hotspot_key(&self) -> &PublicKey {
+        &self.hotspot_key
+    }
+
+    fn bind(
+        self,
+        row: &mut Separated<'_, '_, Postgres, &'static str>,
+        anonymizer: Option<&Anonymizer>,
+    ) {
+        row.push_bind(self.beacon_amount)
+            .push_bind(self.witness_amount)
+            .push_bind(self.epoch_end)
+            .push_bind(anonymize::hotspot_key(&self.hotspot_key, anonymizer));
+    }
+}
+
+/// The table rows of `file_type` are written to, if it is ingested at all.
+pub fn table(file_type: &FileType) -> Option<&'static str> {
+    match file_type {
+        FileType::RadioRewardShare => Some(MobileReward::TABLE),
+        FileType::GatewayRewardShare => Some(IotReward::TABLE),
+        _ => None,
+    }
+}
+
+/// Where a batch of rows came from, recorded alongside each of them.
+pub struct Provenance<'a> {
+    pub source_key: &'a str,
+    /// Request id of the lambda invocation that ingested the rows.
+    pub batch_id: &'a str,
+}
+
+/// Where decoded rows are written, so ingestion can run without a database.
+pub trait RowWriter: Send {
+    /// Inserts `rows` with a single statement, returning how many were new.
+    fn insert<'a, R: Reward + 'a>(
+        &'a mut self,
+        rows: Vec<R>,
+        anonymizer: Option<&'a Anonymizer>,
+        provenance: &'a Provenance<'a>,
+    ) -> BoxFuture<'a, sqlx::Result<u64>>;
+
+    /// Records that the file was ingested, so scheduled sweeps can skip it.
+    /// `partial` marks a salvaged file whose later messages were lost.
+    fn mark_ingested<'a>(
+        &'a mut self,
+        provenance: &'a Provenance<'a>,
+        partial: bool,
+    ) -> BoxFuture<'a, sqlx::Result<()>>;
+}
+
+impl RowWriter for Transaction<'_, Postgres> {
+    fn insert<'a, R: Reward + 'a>(
+        &'a mut self,
+        rows: Vec<R>,
+        anonymizer: Option<&'a Anonymizer>,
+        provenance: &'a Provenance<'a>,
+    ) -> BoxFuture<'a, sqlx::Result<u64>> {
+        insert(self, rows, anonymizer, provenance).boxed()
+    }
+
+    fn mark_ingested<'a>(
+        &'a mut self,
+        provenance: &'a Provenance<'a>,
+        partial: bool,
+    ) -> BoxFuture<'a, sqlx::Result<()>> {
+        mark_ingested(self, provenance, partial).boxed()
+    }
+}
+
+/// Inserts `rows` with a single statement, returning how many were new.
+async fn insert<R: Reward>(
+    tx: &mut Transaction<'_, Postgres>,
+    rows: Vec<R>,
+    anonymizer: Option<&Anonymizer>,
+    provenance: &Provenance<'_>,
+) -> sqlx::Result<u64> {
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let mut query = QueryBuilder::new(format!(
+        "INSERT INTO {} ({}, source_key, ingest_batch_id) ",
+        R::TABLE,
+        R::COLUMNS
+    ));
+    query.push_values(rows, |mut row, reward| {
+        reward.bind(&mut row, anonymizer);
+        row.push_bind(provenance.source_key.to_string())
+            .push_bind(provenance.batch_id.to_string());
+    });
+    query.push(" ON CONFLICT DO NOTHING");
+    let result = query.build().execute(&mut *tx).await?;
+    Ok(result.rows_affected())
+}
+
+async fn mark_ingested(
+    tx: &mut Transaction<'_, Postgres>,
+    provenance: &Provenance<'_>,
+    partial: bool,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO ingested_files (source_key, ingest_batch_id, partial) VALUES ($1, $2, $3) \
+         ON CONFLICT (source_key) DO UPDATE \
+         SET ingest_batch_id = excluded.ingest_batch_id, partial = excluded.partial, \
+         ingested_at = now()",
+    )
+    .bind(provenance.source_key)
+    .bind(provenance.batch_id)
+    .bind(partial)
+    .execute(&mut *tx)
+    .await?;
+    Ok(())
+}
+
+fn timestamp(secs: u64) -> anyhow::Result<DateTime<Utc>> {
+    match Utc.timestamp_opt(secs as i64, 0) {
+        chrono::LocalResult::Single(timestamp) => Ok(timestamp),
+        other => Err(anyhow!("Unexpected end_epoch: {other:?}")),
+    }
+}
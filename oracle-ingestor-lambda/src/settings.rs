@@ -0,0 +1,410 @@
+use crate::{rewards, source};
+use anyhow::anyhow;
+use aws_sdk_s3::model::RequestPayer;
+use file_store::FileType;
+use futures::StreamExt;
+use http::Uri;
+use std::{collections::HashMap, env, str::FromStr, time::Duration};
+
+/// Lambda configuration, read from environment variables and optionally from
+/// SSM Parameter Store and Secrets Manager.
+#[derive(Debug)]
+pub struct Settings {
+    pub database_url: String,
+    /// Number of undecodable messages tolerated per invocation before it fails.
+    pub max_decode_errors: usize,
+    /// Prefix, in the source bucket, that undecodable messages are written under.
+    pub quarantine_prefix: String,
+    /// Stop picking up new files once less than this much invocation time remains.
+    pub deadline_margin: Duration,
+    /// Files taking longer than this are rolled back and reported as failed.
+    pub file_timeout: Option<Duration>,
+    /// Whether a file failing aborts the invocation or is rolled back and skipped.
+    pub failure_policy: FailurePolicy,
+    /// Fraction of files allowed to fail before the whole invocation fails.
+    pub max_failure_rate: f64,
+    /// Keep the messages before a truncated or corrupt part of a file instead
+    /// of failing it.
+    pub salvage_corrupt_files: bool,
+    /// Prefix, in the source bucket, that reports of failed files are written under.
+    pub failure_report_prefix: String,
+    /// CloudWatch namespace for the embedded metrics printed per invocation.
+    pub metrics_namespace: String,
+    /// Attempts made for each S3 request before giving up.
+    pub retry_attempts: u32,
+    /// Upper bound of the first retry delay, doubled on every further attempt.
+    pub retry_base_delay: Duration,
+    /// File type prefixes this deployment ingests; keys of any other type are skipped.
+    pub file_types: Vec<String>,
+    /// Only report which files would be ingested, without reading them or
+    /// touching the database.
+    pub dry_run: bool,
+    /// Read `file://` keys from the local filesystem rather than rejecting them.
+    pub allow_local_files: bool,
+    /// Accept the charges for reading requester pays buckets.
+    pub requester_pays: bool,
+    /// S3 endpoint used instead of AWS's, such as a local MinIO or LocalStack.
+    pub ingest_endpoint: Option<Uri>,
+    /// Rows inserted per statement.
+    pub insert_batch_size: usize,
+    /// Sentry DSN that file failures are reported to.
+    pub sentry_dsn: Option<String>,
+    /// SNS topic each invocation's summary is published to.
+    pub notify_sns_topic_arn: Option<String>,
+    /// URL each invocation's summary is posted to.
+    pub notify_webhook_url: Option<String>,
+    /// Key of a list of the only hotspots whose rows are ingested.
+    pub hotspot_allowlist: Option<String>,
+    /// Key of a list of hotspots whose rows are dropped.
+    pub hotspot_denylist: Option<String>,
+    /// Secrets Manager secret holding the salt used to anonymize hotspot keys.
+    pub anonymize_salt_secret: Option<String>,
+    /// Event keys must start with one of these, if any are given.
+    pub key_prefixes: Vec<String>,
+    /// Event keys must end with one of these, if any are given.
+    pub key_suffixes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailurePolicy {
+    /// The first failing file fails the invocation.
+    FailFast,
+    /// Failing files are rolled back, reported and skipped.
+    Continue,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "fail_fast" => Ok(Self::FailFast),
+            "continue" => Ok(Self::Continue),
+            _ => Err("expected fail_fast or continue".to_string()),
+        }
+    }
+}
+
+impl Settings {
+    /// Loads settings from the environment, falling back to parameters under
+    /// `SETTINGS_SSM_PATH`, and then `SETTINGS_SSM_PATH/SETTINGS_PROFILE`, for
+    /// anything not set there. `DATABASE_URL` may instead be kept in the
+    /// Secrets Manager secret `DATABASE_URL_SECRET`.
+    pub async fn load() -> anyhow::Result<Self> {
+        let mut vars = Vars(env::vars().collect());
+        let ssm_path = vars.get("SETTINGS_SSM_PATH").map(str::to_string);
+        let database_url_secret = vars.get("DATABASE_URL_SECRET").map(str::to_string);
+        if ssm_path.is_some() || database_url_secret.is_some() {
+            let config = aws_config::load_from_env().await;
+            if let Some(path) = ssm_path {
+                let shared = ssm_parameters(&config, &path).await?;
+                let profile = match vars.get("SETTINGS_PROFILE") {
+                    Some(profile) => {
+                        let path = format!("{}/{profile}", path.trim_end_matches('/'));
+                        ssm_parameters(&config, &path).await?
+                    }
+                    None => HashMap::new(),
+                };
+                vars.merge(shared, profile);
+            }
+            if let (Some(secret_id), None) = (database_url_secret, vars.get("DATABASE_URL")) {
+                let database_url = secret_string(&config, &secret_id).await?;
+                vars.0.insert("DATABASE_URL".to_string(), database_url);
+            }
+        }
+        Self::from_vars(&vars)
+    }
+
+    fn from_vars(vars: &Vars) -> anyhow::Result<Self> {
+        let database_url = vars
+            .get("DATABASE_URL")
+            .ok_or_else(|| anyhow!("DATABASE_URL must be set in lambda env variable."))?
+            .to_string();
+        Ok(Self {
+            database_url,
+            max_decode_errors: vars.parse_or("MAX_DECODE_ERRORS", 0)?,
+            quarantine_prefix: vars.parse_or("QUARANTINE_PREFIX", "quarantine/".to_string())?,
+            deadline_margin: Duration::from_secs(vars.parse_or("DEADLINE_MARGIN_SECS", 30)?),
+            file_timeout: match vars.parse_or("FILE_TIMEOUT_SECS", 0)? {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            failure_policy: vars.parse_or("FAILURE_POLICY", FailurePolicy::FailFast)?,
+            max_failure_rate: max_failure_rate(vars)?,
+            salvage_corrupt_files: vars.parse_or("SALVAGE_CORRUPT_FILES", false)?,
+            failure_report_prefix: vars
+                .parse_or("FAILURE_REPORT_PREFIX", "failures/".to_string())?,
+            metrics_namespace: vars.parse_or("METRICS_NAMESPACE", "OracleIngestor".to_string())?,
+            retry_attempts: vars.parse_or("RETRY_ATTEMPTS", 3)?,
+            retry_base_delay: Duration::from_millis(vars.parse_or("RETRY_BASE_DELAY_MS", 200)?),
+            file_types: file_types(vars)?,
+            dry_run: vars.parse_or("DRY_RUN", false)?,
+            allow_local_files: vars.parse_or("ALLOW_LOCAL_FILES", false)?,
+            requester_pays: vars.parse_or("REQUESTER_PAYS", false)?,
+            ingest_endpoint: ingest_endpoint(vars)?,
+            insert_batch_size: insert_batch_size(vars)?,
+            sentry_dsn: vars.get("SENTRY_DSN").map(str::to_string),
+            notify_sns_topic_arn: vars.get("NOTIFY_SNS_TOPIC_ARN").map(str::to_string),
+            notify_webhook_url: vars.get("NOTIFY_WEBHOOK_URL").map(str::to_string),
+            hotspot_allowlist: vars.get("HOTSPOT_ALLOWLIST").map(str::to_string),
+            hotspot_denylist: vars.get("HOTSPOT_DENYLIST").map(str::to_string),
+            anonymize_salt_secret: vars.get("ANONYMIZE_SALT_SECRET").map(str::to_string),
+            key_prefixes: vars.list_or("KEY_PREFIXES", &[]),
+            key_suffixes: vars.list_or("KEY_SUFFIXES", &[]),
+        })
+    }
+
+    /// RequestPayer sent with S3 requests to the source bucket.
+    pub fn request_payer(&self) -> Option<RequestPayer> {
+        self.requester_pays.then_some(RequestPayer::Requester)
+    }
+
+    /// Whether an event for `key` should be handled at all, so events for
+    /// other writers' objects in a shared bucket are ignored.
+    pub fn accepts_event(&self, key: &str) -> bool {
+        let prefixed = self.key_prefixes.is_empty()
+            || self
+                .key_prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix));
+        let suffixed = self.key_suffixes.is_empty()
+            || self.key_suffixes.iter().any(|suffix| key.ends_with(suffix));
+        prefixed && suffixed
+    }
+
+    /// Whether `key` is of one of the configured file types.
+    pub fn ingests(&self, key: &str) -> bool {
+        let prefix = source::prefix(key);
+        self.file_types.iter().any(|file_type| file_type == prefix)
+    }
+}
+
+fn file_types(vars: &Vars) -> anyhow::Result<Vec<String>> {
+    let file_types = vars.list_or(
+        "FILE_TYPES",
+        &["radio_reward_share", "gateway_reward_share"],
+    );
+    for file_type in &file_types {
+        let parsed = FileType::from_str(file_type)
+            .map_err(|err| anyhow!("Invalid FILE_TYPES entry {file_type:?}: {err}"))?;
+        if rewards::table(&parsed).is_none() {
+            return Err(anyhow!(
+                "FILE_TYPES entry {file_type:?} is not supported, expected any of radio_reward_share, gateway_reward_share"
+            ));
+        }
+    }
+    Ok(file_types)
+}
+
+fn max_failure_rate(vars: &Vars) -> anyhow::Result<f64> {
+    match vars.parse_or("MAX_FAILURE_RATE", 1.0)? {
+        rate if (0.0..=1.0).contains(&rate) => Ok(rate),
+        rate => Err(anyhow!(
+            "MAX_FAILURE_RATE must be between 0 and 1, got {rate}"
+        )),
+    }
+}
+
+fn ingest_endpoint(vars: &Vars) -> anyhow::Result<Option<Uri>> {
+    vars.get("INGEST_ENDPOINT")
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|err| anyhow!("Invalid INGEST_ENDPOINT value {value:?}: {err}"))
+        })
+        .transpose()
+}
+
+fn insert_batch_size(vars: &Vars) -> anyhow::Result<usize> {
+    // postgres allows at most 65535 bind parameters per statement and rows
+    // have at most 6 columns
+    const MAX: usize = u16::MAX as usize / 6;
+    match vars.parse_or("INSERT_BATCH_SIZE", 1000)? {
+        size @ 1..=MAX => Ok(size),
+        size => Err(anyhow!(
+            "INSERT_BATCH_SIZE must be between 1 and {MAX}, got {size}"
+        )),
+    }
+}
+
+/// The parameters directly under `path`, keyed by the last segment of their
+/// name, so `/oracle-ingestor/MAX_DECODE_ERRORS` sets `MAX_DECODE_ERRORS`.
+/// Profiles nested below `path` are not included.
+async fn ssm_parameters(
+    config: &aws_types::SdkConfig,
+    path: &str,
+) -> anyhow::Result<HashMap<String, String>> {
+    let client = aws_sdk_ssm::Client::new(config);
+    let mut pages = client
+        .get_parameters_by_path()
+        .path(path)
+        .recursive(false)
+        .with_decryption(true)
+        .into_paginator()
+        .send();
+
+    let mut parameters = HashMap::new();
+    while let Some(page) = pages.next().await {
+        for parameter in page?.parameters().unwrap_or_default() {
+            if let (Some(name), Some(value)) = (parameter.name(), parameter.value()) {
+                let name = name.rsplit('/').next().unwrap_or(name);
+                parameters.insert(name.to_string(), value.to_string());
+            }
+        }
+    }
+    Ok(parameters)
+}
+
+pub async fn secret_string(
+    config: &aws_types::SdkConfig,
+    secret_id: &str,
+) -> anyhow::Result<String> {
+    let client = aws_sdk_secretsmanager::Client::new(config);
+    let secret = client
+        .get_secret_value()
+        .secret_id(secret_id)
+        .send()
+        .await?;
+    secret
+        .secret_string()
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("Secret {secret_id} has no string value"))
+}
+
+struct Vars(HashMap<String, String>);
+
+impl Vars {
+    /// Fills in what the environment doesn't set from SSM parameters, a
+    /// profile's parameters overriding the shared ones under the base path.
+    fn merge(&mut self, shared: HashMap<String, String>, profile: HashMap<String, String>) {
+        let mut parameters = shared;
+        parameters.extend(profile);
+        for (name, value) in parameters {
+            self.0.entry(name).or_insert(value);
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    fn parse_or<T>(&self, name: &str, default: T) -> anyhow::Result<T>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        match self.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|err| anyhow!("Invalid {name} value {value:?}: {err}")),
+            None => Ok(default),
+        }
+    }
+
+    /// Reads a comma separated list, e.g. `a, b,c`.
+    fn list_or(&self, name: &str, default: &[&str]) -> Vec<String> {
+        match self.get(name) {
+            Some(value) => value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect(),
+            None => default.iter().map(|item| item.to_string()).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vars {
+        let mut vars = HashMap::from([(
+            "DATABASE_URL".to_string(),
+            "postgres://localhost/oracles".to_string(),
+        )]);
+        for (name, value) in pairs {
+            vars.insert(name.to_string(), value.to_string());
+        }
+        Vars(vars)
+    }
+
+    fn map(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn requires_database_url() {
+        assert!(Settings::from_vars(&Vars(HashMap::new())).is_err());
+    }
+
+    #[test]
+    fn defaults() {
+        let settings = Settings::from_vars(&vars(&[])).unwrap();
+        assert_eq!(
+            settings.file_types,
+            vec!["radio_reward_share", "gateway_reward_share"]
+        );
+        assert_eq!(settings.max_failure_rate, 1.0);
+        assert_eq!(settings.insert_batch_size, 1000);
+        assert_eq!(settings.failure_policy, FailurePolicy::FailFast);
+    }
+
+    #[test]
+    fn validates_file_types() {
+        let settings = Settings::from_vars(&vars(&[("FILE_TYPES", " radio_reward_share, ")]));
+        assert_eq!(settings.unwrap().file_types, vec!["radio_reward_share"]);
+        assert!(Settings::from_vars(&vars(&[("FILE_TYPES", "bogus")])).is_err());
+    }
+
+    #[test]
+    fn bounds_max_failure_rate() {
+        let rate = |value| Settings::from_vars(&vars(&[("MAX_FAILURE_RATE", value)]));
+        assert_eq!(rate("0").unwrap().max_failure_rate, 0.0);
+        assert_eq!(rate("0.5").unwrap().max_failure_rate, 0.5);
+        assert!(rate("-0.1").is_err());
+        assert!(rate("1.5").is_err());
+        assert!(rate("half").is_err());
+    }
+
+    #[test]
+    fn bounds_insert_batch_size() {
+        let size = |value| Settings::from_vars(&vars(&[("INSERT_BATCH_SIZE", value)]));
+        assert_eq!(size("1").unwrap().insert_batch_size, 1);
+        assert_eq!(size("10922").unwrap().insert_batch_size, 10922);
+        assert!(size("0").is_err());
+        assert!(size("10923").is_err());
+    }
+
+    #[test]
+    fn parses_ingest_endpoint() {
+        let settings = Settings::from_vars(&vars(&[("INGEST_ENDPOINT", "http://localhost:9000")]));
+        assert_eq!(
+            settings.unwrap().ingest_endpoint,
+            Some(Uri::from_static("http://localhost:9000"))
+        );
+        assert!(Settings::from_vars(&vars(&[("INGEST_ENDPOINT", "http://local host")])).is_err());
+    }
+
+    #[test]
+    fn environment_overrides_profile_overrides_shared() {
+        let mut vars = vars(&[("MAX_DECODE_ERRORS", "1")]);
+        vars.merge(
+            map(&[
+                ("MAX_DECODE_ERRORS", "2"),
+                ("QUARANTINE_PREFIX", "shared/"),
+                ("RETRY_ATTEMPTS", "5"),
+            ]),
+            map(&[("MAX_DECODE_ERRORS", "3"), ("QUARANTINE_PREFIX", "prod/")]),
+        );
+
+        let settings = Settings::from_vars(&vars).unwrap();
+        assert_eq!(settings.max_decode_errors, 1);
+        assert_eq!(settings.quarantine_prefix, "prod/");
+        assert_eq!(settings.retry_attempts, 5);
+    }
+}
@@ -0,0 +1,288 @@
+use crate::{retry::RetryPolicy, settings::Settings};
+use anyhow::anyhow;
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use aws_sdk_s3::{
+    model::RequestPayer, output::GetObjectOutput, types::ByteStream, Client, Endpoint,
+};
+use aws_types::SdkConfig;
+use file_store::{BytesMutStream, FileType};
+use futures::{future::BoxFuture, FutureExt, StreamExt, TryStreamExt};
+use std::{error::Error, fmt, fs, io, path::Path, pin::Pin, str::FromStr};
+use tokio::io::{AsyncBufRead, AsyncRead};
+use tokio_util::{
+    codec::{FramedRead, LengthDelimitedCodec},
+    io::StreamReader,
+};
+
+/// Keys with this scheme are read from the local filesystem instead of S3,
+/// so captured oracle files can be replayed, when `ALLOW_LOCAL_FILES` is set.
+pub const FILE_SCHEME: &str = "file://";
+
+/// Oracle files ending in this are zstd rather than gzip compressed.
+pub const ZSTD_EXTENSION: &str = ".zst";
+
+/// Rejects `file://` keys unless local files are allowed, so object keys in
+/// events, manifests and settings can't read the lambda's own filesystem.
+pub fn ensure_allowed(key: &str, allow_local_files: bool) -> anyhow::Result<()> {
+    if key.starts_with(FILE_SCHEME) && !allow_local_files {
+        return Err(anyhow!(
+            "{key} is a local file, which is only read with ALLOW_LOCAL_FILES=true"
+        ));
+    }
+    Ok(())
+}
+
+/// Expands a `file://` directory into the `.gz` and `.zst` files it contains.
+/// Any other key is returned as is.
+pub fn expand(key: &str) -> io::Result<Vec<String>> {
+    let path = match key.strip_prefix(FILE_SCHEME) {
+        Some(path) if Path::new(path).is_dir() => path,
+        _ => return Ok(vec![key.to_string()]),
+    };
+
+    let mut keys = Vec::new();
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|ext| ext == "gz" || ext == "zst")
+        {
+            keys.push(format!("{FILE_SCHEME}{}", path.display()));
+        }
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+/// The file type prefix of the file name, ignoring any directories in the key.
+pub fn prefix(key: &str) -> &str {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    name.split('.').next().unwrap_or("")
+}
+
+pub fn file_type(key: &str) -> file_store::Result<FileType> {
+    FileType::from_str(prefix(key))
+}
+
+/// Where oracle files are read from, so ingestion can run against something
+/// other than S3.
+pub trait InputSource: Send + Sync {
+    /// Opens an oracle file as a stream of its messages.
+    fn open<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<BytesMutStream>>;
+}
+
+/// Where quarantined messages and failure reports are written.
+pub trait ObjectSink: Send + Sync {
+    fn put<'a>(&'a self, key: &'a str, body: Vec<u8>) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+/// S3 client config for the source bucket, sending requests to
+/// `INGEST_ENDPOINT`, such as a local MinIO, when it's set. Buckets are
+/// addressed path-style, which MinIO and LocalStack both accept.
+pub fn client_config(settings: &Settings, config: &SdkConfig) -> aws_sdk_s3::config::Builder {
+    let builder = aws_sdk_s3::config::Builder::from(config);
+    match &settings.ingest_endpoint {
+        Some(endpoint) => builder.endpoint_resolver(Endpoint::immutable(endpoint.clone())),
+        None => builder,
+    }
+}
+
+/// The event bucket, which oracle files are read from (or the local
+/// filesystem for `file://` keys) and quarantined messages are written to.
+pub struct Bucket<'a> {
+    pub client: &'a Client,
+    pub retry: &'a RetryPolicy,
+    pub bucket: &'a str,
+    /// Sent with every request made here, for requester pays buckets.
+    pub request_payer: Option<RequestPayer>,
+}
+
+impl Bucket<'_> {
+    /// Reads a whole plain object, such as a manifest or key list, as opposed
+    /// to an oracle file of length delimited messages.
+    pub async fn read(&self, key: &str) -> anyhow::Result<Vec<u8>> {
+        match key.strip_prefix(FILE_SCHEME) {
+            Some(path) => Ok(fs::read(path)?),
+            None => Ok(self
+                .get(key)
+                .await?
+                .body
+                .collect()
+                .await?
+                .into_bytes()
+                .to_vec()),
+        }
+    }
+
+    /// Size in bytes of the compressed file behind `key`.
+    pub async fn size(&self, key: &str) -> anyhow::Result<u64> {
+        match key.strip_prefix(FILE_SCHEME) {
+            Some(path) => Ok(fs::metadata(path)?.len()),
+            None => {
+                let object = self
+                    .retry
+                    .run("head", || {
+                        self.client
+                            .head_object()
+                            .bucket(self.bucket)
+                            .key(key)
+                            .set_request_payer(self.request_payer.clone())
+                            .send()
+                    })
+                    .await?;
+                Ok(object.content_length() as u64)
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<GetObjectOutput> {
+        let object = self
+            .retry
+            .run("get", || {
+                self.client
+                    .get_object()
+                    .bucket(self.bucket)
+                    .key(key)
+                    .set_request_payer(self.request_payer.clone())
+                    .send()
+            })
+            .await?;
+        Ok(object)
+    }
+}
+
+impl InputSource for Bucket<'_> {
+    fn open<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<BytesMutStream>> {
+        open(self, key).boxed()
+    }
+}
+
+impl ObjectSink for Bucket<'_> {
+    fn put<'a>(&'a self, key: &'a str, body: Vec<u8>) -> BoxFuture<'a, anyhow::Result<()>> {
+        put(self, key, body).boxed()
+    }
+}
+
+/// Opens an oracle file as a stream of its messages, decompressing it as
+/// gzip, or as zstd when the key ends in `.zst`. S3 objects are fetched here
+/// rather than through file_store so the download goes through the retry
+/// policy and carries RequestPayer.
+async fn open(bucket: &Bucket<'_>, key: &str) -> anyhow::Result<BytesMutStream> {
+    let reader: Pin<Box<dyn AsyncBufRead + Send>> = match key.strip_prefix(FILE_SCHEME) {
+        // read up front, so the stream's only errors are the file's own
+        Some(path) => Box::pin(io::Cursor::new(tokio::fs::read(path).await?)),
+        None => {
+            let body = bucket.get(key).await?.body;
+            let body = body.map_err(|err| io::Error::other(ReadError(err.into())));
+            Box::pin(StreamReader::new(body))
+        }
+    };
+    if key.ends_with(ZSTD_EXTENSION) {
+        Ok(message_source(ZstdDecoder::new(reader)))
+    } else {
+        let mut decoder = GzipDecoder::new(reader);
+        decoder.multiple_members(true);
+        Ok(message_source(decoder))
+    }
+}
+
+async fn put(bucket: &Bucket<'_>, key: &str, body: Vec<u8>) -> anyhow::Result<()> {
+    bucket
+        .retry
+        .run("put", || {
+            bucket
+                .client
+                .put_object()
+                .bucket(bucket.bucket)
+                .key(key)
+                .body(ByteStream::from(body.clone()))
+                .set_request_payer(bucket.request_payer.clone())
+                .send()
+        })
+        .await?;
+    Ok(())
+}
+
+/// A failure reading an object's body, as opposed to decoding it, so a
+/// dropped connection isn't mistaken for a corrupt file.
+#[derive(Debug)]
+pub struct ReadError(pub Box<dyn Error + Send + Sync>);
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unable to read object: {}", self.0)
+    }
+}
+
+impl Error for ReadError {}
+
+/// Whether an oracle file's stream failed because reading it did, rather
+/// than because the file is truncated or corrupt.
+pub fn is_read_error(err: &file_store::Error) -> bool {
+    matches!(err, file_store::Error::Io(err)
+        if err.get_ref().is_some_and(|inner| inner.is::<ReadError>()))
+}
+
+/// Splits decompressed data into messages, framed the way file_store writes
+/// them with a big endian u32 length prefix.
+fn message_source(reader: impl AsyncRead + Send + 'static) -> BytesMutStream {
+    FramedRead::new(reader, LengthDelimitedCodec::new())
+        .map(|result| result.map_err(file_store::Error::from))
+        .boxed()
+}
+
+/// Oracle files held in memory, and the objects written back, for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryBucket {
+    files: std::collections::HashMap<String, Vec<Vec<u8>>>,
+    failures: std::collections::HashMap<String, fn() -> io::Error>,
+    pub puts: std::sync::Mutex<Vec<(String, Vec<u8>)>>,
+}
+
+#[cfg(test)]
+impl MemoryBucket {
+    /// Adds a file of encoded messages.
+    pub fn insert<M: helium_proto::Message>(&mut self, key: &str, messages: &[M]) {
+        let messages = messages.iter().map(|msg| msg.encode_to_vec()).collect();
+        self.insert_raw(key, messages);
+    }
+
+    /// Adds a file of raw, possibly undecodable, messages.
+    pub fn insert_raw(&mut self, key: &str, messages: Vec<Vec<u8>>) {
+        self.files.insert(key.to_string(), messages);
+    }
+
+    /// Makes the file's stream fail with `error` after its messages.
+    pub fn fail_after(&mut self, key: &str, error: fn() -> io::Error) {
+        self.failures.insert(key.to_string(), error);
+    }
+}
+
+#[cfg(test)]
+impl InputSource for MemoryBucket {
+    fn open<'a>(&'a self, key: &'a str) -> BoxFuture<'a, anyhow::Result<BytesMutStream>> {
+        let result = match self.files.get(key) {
+            Some(messages) => {
+                let mut messages: Vec<file_store::Result<bytes::BytesMut>> = messages
+                    .iter()
+                    .map(|msg| Ok(bytes::BytesMut::from(&msg[..])))
+                    .collect();
+                if let Some(error) = self.failures.get(key) {
+                    messages.push(Err(error().into()));
+                }
+                Ok(futures::stream::iter(messages).boxed())
+            }
+            None => Err(anyhow::anyhow!("No file {key}")),
+        };
+        futures::future::ready(result).boxed()
+    }
+}
+
+#[cfg(test)]
+impl ObjectSink for MemoryBucket {
+    fn put<'a>(&'a self, key: &'a str, body: Vec<u8>) -> BoxFuture<'a, anyhow::Result<()>> {
+        self.puts.lock().unwrap().push((key.to_string(), body));
+        futures::future::ready(Ok(())).boxed()
+    }
+}
@@ -0,0 +1,58 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Outcome of an invocation, returned to the caller so orchestrators such as
+/// Step Functions can branch on it.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub files_processed: usize,
+    pub messages_read: usize,
+    /// Rows actually inserted per table, excluding conflicts with existing rows.
+    pub rows_written: BTreeMap<&'static str, u64>,
+    pub decode_errors: usize,
+    /// Rows dropped by the hotspot allowlist or denylist.
+    pub rows_filtered: usize,
+    /// S3 requests retried after a transient failure.
+    pub retries: usize,
+    /// Keys whose file type this deployment is not configured to ingest.
+    pub skipped_keys: Vec<String>,
+    /// Files that were rolled back after failing, with `FAILURE_POLICY=continue`,
+    /// or exceeding `FILE_TIMEOUT_SECS`.
+    pub failed: Vec<FailedFile>,
+    /// Files cut short by corruption, whose messages before it were kept.
+    pub salvaged_keys: Vec<String>,
+    /// Keys left over when the invocation stopped early, to be retried.
+    pub unprocessed_keys: Vec<String>,
+    /// Files that would be ingested, only reported for dry runs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub plan: Vec<PlannedFile>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedFile {
+    pub key: String,
+    pub size: u64,
+    pub table: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailedFile {
+    pub key: String,
+    pub error: String,
+}
+
+impl Summary {
+    pub fn add_rows(&mut self, table: &'static str, rows: u64) {
+        *self.rows_written.entry(table).or_default() += rows;
+    }
+
+    /// Adds the counts of a single file's summary.
+    pub fn merge(&mut self, file: Summary) {
+        self.messages_read += file.messages_read;
+        self.rows_filtered += file.rows_filtered;
+        self.salvaged_keys.extend(file.salvaged_keys);
+        for (table, rows) in file.rows_written {
+            self.add_rows(table, rows);
+        }
+    }
+}
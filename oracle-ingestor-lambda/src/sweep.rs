@@ -0,0 +1,74 @@
+use crate::{event::Target, settings::Settings, source};
+use aws_sdk_s3::Region;
+use chrono::Utc;
+use futures::StreamExt;
+use serde::Deserialize;
+use sqlx::postgres::PgPool;
+use std::collections::HashSet;
+
+/// Input of an EventBridge scheduled rule, sweeping a bucket for recent
+/// files whose S3 notification was lost.
+#[derive(Debug, Deserialize)]
+pub struct Sweep {
+    pub bucket: String,
+    pub region: String,
+    /// How far back from now to look for files.
+    pub window_minutes: u32,
+}
+
+/// Lists the files of every ingested type written within the window that
+/// have not been ingested yet.
+pub async fn target(settings: &Settings, pool: &PgPool, sweep: Sweep) -> anyhow::Result<Target> {
+    let config = aws_config::from_env()
+        .region(Region::new(sweep.region.clone()))
+        .load()
+        .await;
+    let client = aws_sdk_s3::Client::from_conf(source::client_config(settings, &config).build());
+    let after = Utc::now() - chrono::Duration::minutes(sweep.window_minutes.into());
+
+    let mut keys = Vec::new();
+    for file_type in &settings.file_types {
+        // keys are `<type>.<millis>.gz`, so listing after the window's start
+        // skips everything older
+        let mut pages = client
+            .list_objects_v2()
+            .bucket(&sweep.bucket)
+            .set_request_payer(settings.request_payer())
+            .prefix(format!("{file_type}."))
+            .start_after(format!("{file_type}.{}", after.timestamp_millis()))
+            .into_paginator()
+            .send();
+        while let Some(page) = pages.next().await {
+            for object in page?.contents().unwrap_or_default() {
+                if let Some(key) = object.key() {
+                    keys.push(key.to_string());
+                }
+            }
+        }
+    }
+    let listed = keys.len();
+
+    // dry runs leave the database alone, so report everything in the window
+    if !settings.dry_run {
+        let ingested: HashSet<String> =
+            sqlx::query_scalar("SELECT source_key FROM ingested_files WHERE source_key = ANY($1)")
+                .bind(&keys)
+                .fetch_all(pool)
+                .await?
+                .into_iter()
+                .collect();
+        keys.retain(|key| !ingested.contains(key));
+    }
+    tracing::info!(
+        listed,
+        window_minutes = sweep.window_minutes,
+        not_ingested = keys.len(),
+        "swept bucket"
+    );
+
+    Ok(Target {
+        bucket: sweep.bucket,
+        region: sweep.region,
+        keys,
+    })
+}
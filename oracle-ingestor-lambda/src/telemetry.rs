@@ -0,0 +1,75 @@
+use tracing::Subscriber;
+use tracing_subscriber::{
+    layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, Layer,
+};
+
+/// Installs the log formatter, emitting one JSON object per event when
+/// `LOG_FORMAT=json`, and an OTLP span exporter when built with the `otel`
+/// feature and `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+pub fn init() -> anyhow::Result<()> {
+    // lambda already timestamps every line it captures
+    let fmt = tracing_subscriber::fmt::layer()
+        .without_time()
+        .with_ansi(false);
+    // read from the environment only, since settings are loaded, and may fail
+    // to load, after logging is installed
+    let fmt = match std::env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => fmt.json().flatten_event(true).boxed(),
+        _ => fmt.boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(fmt)
+        .with(otel_layer()?)
+        .try_init()?;
+    Ok(())
+}
+
+/// Provider of the batched OTLP exporter, flushed at the end of every
+/// invocation.
+#[cfg(feature = "otel")]
+static PROVIDER: std::sync::OnceLock<opentelemetry::sdk::trace::TracerProvider> =
+    std::sync::OnceLock::new();
+
+#[cfg(feature = "otel")]
+fn otel_layer<S>() -> anyhow::Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err() {
+        return Ok(None);
+    }
+
+    // the tonic exporter needs the tokio runtime, which the batch processor
+    // runs it on; a frozen lambda can't export, so `flush` drains each batch
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .install_batch(opentelemetry::runtime::Tokio)?;
+    if let Some(provider) = tracer.provider() {
+        let _ = PROVIDER.set(provider);
+    }
+    Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+}
+
+/// Exports the invocation's spans before the lambda freezes. The flush
+/// blocks, so it runs off the async workers.
+#[cfg(feature = "otel")]
+pub async fn flush() {
+    if let Some(provider) = PROVIDER.get() {
+        let flushed = tokio::task::spawn_blocking(move || provider.force_flush()).await;
+        if !flushed.is_ok_and(|results| results.iter().all(Result::is_ok)) {
+            tracing::warn!("unable to flush spans");
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub async fn flush() {}
+
+#[cfg(not(feature = "otel"))]
+fn otel_layer<S>() -> anyhow::Result<Option<impl Layer<S>>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    Ok(None::<tracing_subscriber::layer::Identity>)
+}